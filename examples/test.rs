@@ -1,7 +1,7 @@
-use rust_nonblocking_logger::NonBlockingLogger;
+use log_nonblock::NonBlockingLogger;
 
 fn main() {
-    NonBlockingLogger::new().init().unwrap();
+    let _logger = NonBlockingLogger::builder().init().unwrap();
 
     log::warn!("This is an example message.");
 
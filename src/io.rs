@@ -4,6 +4,10 @@ use std::io::Write;
 #[cfg(unix)]
 use std::os::fd::{AsRawFd, RawFd};
 
+// Non-Unix targets have no pollable fd; the retry loop degrades to plain retry.
+#[cfg(not(unix))]
+pub(crate) type RawFd = i32;
+
 /// Sets a file descriptor to non-blocking mode on Unix systems
 #[cfg(unix)]
 pub fn set_nonblocking(fd: RawFd) -> Result<(), io::Error> {
@@ -43,74 +47,97 @@ pub(crate) fn wait_writable(fd: RawFd) -> Result<(), io::Error> {
     }
 }
 
-macro_rules! write_with_retry_internal {
-    ($out:expr, $msg:expr) => {{
-        let mut out = $out;
-        let bytes = $msg.as_bytes();
-        let mut written = 0;
-
-        #[cfg(unix)]
-        let raw_fd = out.as_raw_fd();
-
-        while written < bytes.len() {
-            match out.write(&bytes[written..]) {
-                Ok(0) => {
-                    #[cfg(unix)]
-                    {
-                        // Nothing accepted, wait for fd to become writable
-                        if wait_writable(raw_fd).is_err() {
-                            // If poll fails, give up
-                            break;
-                        }
-                    }
-
-                    #[cfg(windows)]
-                    {
-                        // On Windows, just retry
-                    }
-                }
-                Ok(n) => {
-                    // Remove written bytes
-                    written += n;
-                }
-                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-                    #[cfg(unix)]
-                    {
-                        // Wait for fd to become writable
-                        if wait_writable(raw_fd).is_err() {
-                            // If poll fails, give up
-                            break;
-                        }
-                    }
-
-                    #[cfg(windows)]
-                    {
-                        // On Windows, just retry
-                    }
+/// Reacts to a stalled write: polls the fd for writability when one is known,
+/// otherwise yields and retries. Returns `false` if the caller should give up.
+fn wait_or_retry(fd: Option<RawFd>) -> bool {
+    #[cfg(unix)]
+    {
+        match fd {
+            Some(fd) => wait_writable(fd).is_ok(),
+            None => {
+                // No pollable fd (e.g. an in-memory buffer): just retry.
+                std::thread::yield_now();
+                true
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = fd;
+        true
+    }
+}
+
+/// Writes all of `bytes` to `out` with retry logic.
+///
+/// When `fd` is `Some`, a `WouldBlock`/`Ok(0)` stall polls the descriptor for
+/// writability (`POLLOUT`); when it is `None` the loop degrades to a plain
+/// yield-and-retry so in-memory or non-fd sinks still drain. Gives up on a hard
+/// error.
+pub(crate) fn write_all_with_retry(out: &mut dyn Write, fd: Option<RawFd>, bytes: &[u8]) {
+    let mut written = 0;
+
+    while written < bytes.len() {
+        match out.write(&bytes[written..]) {
+            Ok(0) => {
+                if !wait_or_retry(fd) {
+                    break;
                 }
-                Err(_) => {
-                    // Hard error, give up
+            }
+            Ok(n) => {
+                written += n;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if !wait_or_retry(fd) {
                     break;
                 }
             }
+            Err(_) => {
+                // Hard error, give up
+                break;
+            }
         }
-    }};
+    }
+}
+
+/// Writes a record to STDOUT with retry logic, without adding any prefix.
+///
+/// This is the hot path of the background writer thread: the locked handle is
+/// acquired per call, which is cheap because a single thread owns the sink.
+pub(crate) fn write_stdout(msg: &str) {
+    let out = io::stdout();
+    let mut lock = out.lock();
+    #[cfg(unix)]
+    let fd = Some(lock.as_raw_fd());
+    #[cfg(not(unix))]
+    let fd = None;
+    write_all_with_retry(&mut lock, fd, msg.as_bytes());
+}
+
+/// Writes a record to STDERR with retry logic, without adding any prefix.
+pub(crate) fn write_stderr(msg: &str) {
+    let out = io::stderr();
+    let mut lock = out.lock();
+    #[cfg(unix)]
+    let fd = Some(lock.as_raw_fd());
+    #[cfg(not(unix))]
+    let fd = None;
+    write_all_with_retry(&mut lock, fd, msg.as_bytes());
 }
 
 /// Internal function for writing error messages to STDERR with retry logic.
 #[allow(unused)]
 pub(crate) fn write_stderr_with_retry_internal(msg: &str) {
-    let out = io::stderr();
     let formatted = format!("[log_nonblock error] {}\n", msg);
-    write_with_retry_internal!(out.lock(), &formatted);
+    write_stderr(&formatted);
 }
 
 /// Internal function for writing error messages to STDOUT with retry logic.
 #[allow(unused)]
 pub(crate) fn write_stdout_with_retry_internal(msg: &str) {
-    let out = io::stdout();
     let formatted = format!("[log_nonblock error] {}\n", msg);
-    write_with_retry_internal!(out.lock(), &formatted);
+    write_stdout(&formatted);
 }
 
 /// Writes a message to stdout with retry logic, without adding any prefix.
@@ -118,8 +145,7 @@ pub(crate) fn write_stdout_with_retry_internal(msg: &str) {
 #[doc(hidden)]
 #[cfg(feature = "macros")]
 pub fn write_stdout_with_retry(msg: &str) {
-    let out = io::stdout();
-    write_with_retry_internal!(out.lock(), msg);
+    write_stdout(msg);
 }
 
 /// Writes a message to stderr with retry logic, without adding any prefix.
@@ -127,6 +153,5 @@ pub fn write_stdout_with_retry(msg: &str) {
 #[doc(hidden)]
 #[cfg(feature = "macros")]
 pub fn write_stderr_with_retry(msg: &str) {
-    let out = io::stderr();
-    write_with_retry_internal!(out.lock(), msg);
+    write_stderr(msg);
 }
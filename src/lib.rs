@@ -0,0 +1,853 @@
+//! A non-blocking logger for the [`log`] facade.
+//!
+//! The crate installs a [`log::Log`] implementation whose hot path only formats
+//! a record and hands it to a dedicated background writer thread over a bounded
+//! buffer. Application threads never touch the output file descriptor and never
+//! poll for writability, so a slow consumer on the other end of stdout/stderr
+//! can never stall a call to `log::warn!`.
+//!
+//! When the producer outpaces the sink the buffer's [`OverflowPolicy`] decides
+//! whether to drop the newest record, evict the oldest, or block the producer.
+//! Dropped records are counted and the loss is surfaced as a synthesized record
+//! so it never goes unnoticed.
+
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle, ThreadId};
+
+#[cfg(unix)]
+use std::os::fd::RawFd;
+
+use log::{LevelFilter, Metadata, Record, SetLoggerError};
+
+mod file_writer;
+mod io;
+mod tcp;
+
+pub use file_writer::FileWriter;
+pub use io::set_nonblocking;
+pub use tcp::TcpSink;
+
+/// Default maximum number of buffered records.
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Default byte budget for the buffer (4 MiB, mirroring the Fuchsia logger cap).
+const DEFAULT_BUFFER_BYTES: usize = 4 * 1024 * 1024;
+
+/// What the buffer does when it is full and another record arrives.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OverflowPolicy {
+    /// Drop the incoming record, leaving the backlog untouched.
+    DropNewest,
+    /// Evict the oldest buffered record(s) to make room (FIFO eviction).
+    DropOldest,
+    /// Block the producer until the writer drains enough room.
+    Block,
+}
+
+/// Which standard stream a formatted record is destined for.
+#[derive(Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// A formatted record ready to be written by the background thread.
+#[derive(Clone)]
+struct Line {
+    stream: Stream,
+    text: String,
+}
+
+/// A predicate over a record, used to route records to a particular sink.
+///
+/// A record matches when its level is at or above `min_severity`, its target
+/// contains at least one configured tag substring (or no tags are configured),
+/// and it originates from `thread_id` (or no thread is configured). Filters are
+/// evaluated before formatting, so a non-matching route costs nothing.
+#[derive(Clone)]
+pub struct Filter {
+    min_severity: LevelFilter,
+    tags: HashSet<String>,
+    thread_id: Option<ThreadId>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new()
+    }
+}
+
+impl Filter {
+    /// Creates a filter that accepts every record at or below the global level.
+    pub fn new() -> Self {
+        Filter {
+            min_severity: LevelFilter::Trace,
+            tags: HashSet::new(),
+            thread_id: None,
+        }
+    }
+
+    /// Restricts the route to records at or above `level`.
+    pub fn with_min_severity(mut self, level: LevelFilter) -> Self {
+        self.min_severity = level;
+        self
+    }
+
+    /// Adds a target substring the record's `target()` must contain to match.
+    pub fn with_tag<S: Into<String>>(mut self, tag: S) -> Self {
+        self.tags.insert(tag.into());
+        self
+    }
+
+    /// Restricts the route to records emitted from `thread_id`.
+    pub fn with_thread_id(mut self, thread_id: ThreadId) -> Self {
+        self.thread_id = Some(thread_id);
+        self
+    }
+
+    /// Returns true if `record` (emitted from `current`) should be routed here.
+    fn matches(&self, record: &Record, current: ThreadId) -> bool {
+        if record.level() > self.min_severity {
+            return false;
+        }
+        if !self.tags.is_empty() && !self.tags.iter().any(|t| record.target().contains(t)) {
+            return false;
+        }
+        if let Some(expected) = self.thread_id {
+            if expected != current {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// A message handed from a producer to the writer thread.
+enum Message {
+    /// A formatted record to write.
+    Record(Line),
+    /// A flush barrier: the writer acknowledges once all prior records drained.
+    Flush(SyncSender<()>),
+    /// Stop the writer loop and let the thread exit.
+    Shutdown,
+}
+
+/// Mutable state guarded by the buffer's mutex.
+struct BufferInner {
+    queue: VecDeque<Message>,
+    bytes: usize,
+}
+
+/// A bounded, thread-safe buffer between the producers and the writer thread.
+///
+/// Records count against both a message-count bound and a byte budget; control
+/// messages (flush/shutdown) bypass the bounds so shutdown can never deadlock.
+struct Buffer {
+    inner: Mutex<BufferInner>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    max_messages: usize,
+    max_bytes: usize,
+    policy: OverflowPolicy,
+    dropped: AtomicUsize,
+}
+
+impl Buffer {
+    fn new(max_messages: usize, max_bytes: usize, policy: OverflowPolicy) -> Self {
+        Buffer {
+            inner: Mutex::new(BufferInner {
+                queue: VecDeque::new(),
+                bytes: 0,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            max_messages,
+            max_bytes,
+            policy,
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// True if admitting `incoming` bytes would exceed either bound, given a
+    /// buffer that already holds at least one record.
+    fn is_full(&self, inner: &BufferInner, incoming: usize) -> bool {
+        !inner.queue.is_empty()
+            && (inner.queue.len() >= self.max_messages
+                || inner.bytes + incoming > self.max_bytes)
+    }
+
+    /// Enqueues a formatted record, honouring the configured overflow policy.
+    ///
+    /// Never blocks under `DropNewest`/`DropOldest`; only `Block` waits.
+    fn push_record(&self, line: Line) {
+        let size = line.text.len();
+        let mut inner = self.inner.lock().unwrap();
+
+        if self.is_full(&inner, size) {
+            match self.policy {
+                OverflowPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                OverflowPolicy::DropOldest => {
+                    while self.is_full(&inner, size) {
+                        if let Some(evicted) = Self::pop_record(&mut inner) {
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                            drop(evicted);
+                        } else {
+                            break;
+                        }
+                    }
+                }
+                OverflowPolicy::Block => {
+                    while self.is_full(&inner, size) {
+                        inner = self.not_full.wait(inner).unwrap();
+                    }
+                }
+            }
+        }
+
+        inner.bytes += size;
+        inner.queue.push_back(Message::Record(line));
+        self.not_empty.notify_one();
+    }
+
+    /// Enqueues a control message, bypassing the bounds.
+    fn push_control(&self, message: Message) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.queue.push_back(message);
+        self.not_empty.notify_one();
+    }
+
+    /// Pops the oldest record from the front, skipping control messages, and
+    /// updates the byte accounting. Used only for FIFO eviction.
+    fn pop_record(inner: &mut BufferInner) -> Option<Message> {
+        let idx = inner
+            .queue
+            .iter()
+            .position(|m| matches!(m, Message::Record(_)))?;
+        let message = inner.queue.remove(idx)?;
+        if let Message::Record(ref line) = message {
+            inner.bytes -= line.text.len();
+        }
+        Some(message)
+    }
+
+    /// Blocks until a message is available, then returns it.
+    fn pop(&self) -> Message {
+        let mut inner = self.inner.lock().unwrap();
+        while inner.queue.is_empty() {
+            inner = self.not_empty.wait(inner).unwrap();
+        }
+        let message = inner.queue.pop_front().unwrap();
+        if let Message::Record(ref line) = message {
+            inner.bytes -= line.text.len();
+            self.not_full.notify_one();
+        }
+        message
+    }
+
+    /// Returns the number of records dropped since the counter was last reset
+    /// by [`take_dropped`](Self::take_dropped), without resetting it.
+    fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Atomically reads and clears the dropped counter.
+    fn take_dropped(&self) -> usize {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+/// A user-supplied output sink for the logger.
+///
+/// Any `Write + Send` can back a sink; implement [`Sink::raw_fd`] to hand the
+/// writer a pollable descriptor so a `WouldBlock` stall waits on `POLLOUT`
+/// instead of spinning. The default returns `None`, which is correct for
+/// in-memory buffers (such as the `Sink(Arc<Mutex<Vec<u8>>>)` wrapper used to
+/// capture output in tests) and degrades gracefully to plain retry.
+pub trait Sink: Write + Send {
+    /// The raw fd backing this sink, if any.
+    #[cfg(unix)]
+    fn raw_fd(&self) -> Option<RawFd> {
+        None
+    }
+}
+
+/// A routed destination: records matching `filter` are delivered to `buffer`.
+///
+/// The default destination has no filter and receives every record that passes
+/// the global level.
+struct Channel {
+    filter: Option<Filter>,
+    buffer: Arc<Buffer>,
+}
+
+/// The [`log::Log`] implementation installed into the `log` facade.
+struct LogAdapter {
+    channels: Vec<Channel>,
+    level: LevelFilter,
+    timestamps: bool,
+}
+
+impl LogAdapter {
+    /// Formats a record the way the writer thread expects to receive it.
+    fn format(&self, record: &Record) -> Line {
+        let stream = match record.level() {
+            log::Level::Error | log::Level::Warn => Stream::Stderr,
+            _ => Stream::Stdout,
+        };
+
+        let text = if self.timestamps {
+            let secs = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            format!(
+                "[{}] {} {}: {}\n",
+                secs,
+                record.level(),
+                record.target(),
+                record.args()
+            )
+        } else {
+            format!("{} {}: {}\n", record.level(), record.target(), record.args())
+        };
+
+        Line { stream, text }
+    }
+}
+
+impl log::Log for LogAdapter {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let current = thread::current().id();
+        let mut line: Option<Line> = None;
+        for channel in &self.channels {
+            let matched = match channel.filter {
+                Some(ref filter) => filter.matches(record, current),
+                None => true,
+            };
+            if !matched {
+                continue;
+            }
+            // Format lazily on the first matching channel, then reuse.
+            let formatted = line.get_or_insert_with(|| self.format(record));
+            channel.buffer.push_record(formatted.clone());
+        }
+    }
+
+    fn flush(&self) {
+        // Send a barrier to every channel, then wait for each acknowledgement.
+        let acks: Vec<_> = self
+            .channels
+            .iter()
+            .map(|channel| {
+                let (ack_tx, ack_rx) = sync_channel(0);
+                channel.buffer.push_control(Message::Flush(ack_tx));
+                ack_rx
+            })
+            .collect();
+        // Block the caller of `flush()` (only) until every barrier drains.
+        for ack_rx in acks {
+            let _ = ack_rx.recv();
+        }
+    }
+}
+
+/// Emits a synthesized record reporting dropped messages, if any, and resets
+/// the counter so the loss is reported exactly once.
+fn report_dropped(buffer: &Buffer) {
+    let dropped = buffer.take_dropped();
+    if dropped > 0 {
+        io::write_stderr(&format!("[log_nonblock] dropped {} messages\n", dropped));
+    }
+}
+
+/// The destination the writer thread drains records to.
+enum Output {
+    /// Route records to stdout/stderr by level (the default).
+    Stdio,
+    /// Route records into a memory-mapped ring-buffer file sink.
+    File(Arc<FileWriter>),
+    /// Route records to a user-supplied `Write` sink.
+    Custom(Box<dyn Sink>),
+}
+
+impl Output {
+    /// Writes one formatted record to the destination.
+    fn write(&mut self, line: &Line) {
+        match self {
+            Output::Stdio => match line.stream {
+                Stream::Stdout => io::write_stdout(&line.text),
+                Stream::Stderr => io::write_stderr(&line.text),
+            },
+            Output::File(writer) => writer.write(line.text.as_bytes()),
+            Output::Custom(sink) => {
+                // Poll on a real fd (pipe/file/socket); plain retry otherwise.
+                #[cfg(unix)]
+                let fd = sink.raw_fd();
+                #[cfg(not(unix))]
+                let fd = None;
+                io::write_all_with_retry(sink.as_mut(), fd, line.text.as_bytes());
+            }
+        }
+    }
+
+    /// Flushes any buffered output held by the destination.
+    fn flush(&mut self) {
+        match self {
+            Output::Stdio => {}
+            Output::File(writer) => writer.flush(),
+            Output::Custom(sink) => {
+                let _ = sink.flush();
+            }
+        }
+    }
+}
+
+/// Drives the buffer, owning the retry/poll loop and the destination sink.
+fn writer_loop(buffer: Arc<Buffer>, mut output: Output) {
+    loop {
+        match buffer.pop() {
+            Message::Record(line) => {
+                output.write(&line);
+                // Surface any overflow loss once the sink has caught its breath.
+                report_dropped(&buffer);
+            }
+            Message::Flush(ack) => {
+                output.flush();
+                report_dropped(&buffer);
+                let _ = ack.send(());
+            }
+            Message::Shutdown => {
+                report_dropped(&buffer);
+                break;
+            }
+        }
+    }
+}
+
+/// The error returned when a logger cannot be installed.
+///
+/// Installation fails either because a logger is already registered with the
+/// `log` facade or because a configured file sink could not be opened.
+#[derive(Debug)]
+pub enum InitError {
+    /// A logger was already installed into the `log` facade.
+    SetLogger(SetLoggerError),
+    /// The configured file sink could not be opened.
+    Io(std::io::Error),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::SetLogger(e) => write!(f, "{}", e),
+            InitError::Io(e) => write!(f, "failed to open log_nonblock file sink: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for InitError {}
+
+impl From<SetLoggerError> for InitError {
+    fn from(e: SetLoggerError) -> Self {
+        InitError::SetLogger(e)
+    }
+}
+
+impl From<std::io::Error> for InitError {
+    fn from(e: std::io::Error) -> Self {
+        InitError::Io(e)
+    }
+}
+
+/// Builder for [`NonBlockingLogger`].
+pub struct NonBlockingLoggerBuilder {
+    level: LevelFilter,
+    timestamps: bool,
+    capacity: usize,
+    buffer_bytes: usize,
+    policy: OverflowPolicy,
+    file: Option<PathBuf>,
+    file_buffer_size: usize,
+    compression: bool,
+    sink: Option<Box<dyn Sink>>,
+    routes: Vec<(Filter, Box<dyn Sink>)>,
+}
+
+impl Default for NonBlockingLoggerBuilder {
+    fn default() -> Self {
+        NonBlockingLoggerBuilder {
+            level: LevelFilter::Info,
+            timestamps: true,
+            capacity: DEFAULT_CAPACITY,
+            buffer_bytes: DEFAULT_BUFFER_BYTES,
+            policy: OverflowPolicy::DropNewest,
+            file: None,
+            file_buffer_size: file_writer::DEFAULT_BUFFER_SIZE,
+            compression: false,
+            sink: None,
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl NonBlockingLoggerBuilder {
+    /// Creates a builder with the default configuration.
+    pub fn new() -> Self {
+        NonBlockingLoggerBuilder::default()
+    }
+
+    /// Sets the maximum level that will be logged.
+    pub fn with_level(mut self, level: LevelFilter) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Disables the timestamp prefix on every record.
+    pub fn without_timestamps(mut self) -> Self {
+        self.timestamps = false;
+        self
+    }
+
+    /// Sets the maximum number of buffered records.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Sets the byte budget of the buffer.
+    pub fn with_buffer_bytes(mut self, bytes: usize) -> Self {
+        self.buffer_bytes = bytes;
+        self
+    }
+
+    /// Sets the policy applied when the buffer is full.
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Routes records into a memory-mapped ring-buffer file sink at `path`
+    /// instead of stdout/stderr.
+    pub fn with_file<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.file = Some(path.into());
+        self
+    }
+
+    /// Sets the size of the mapped ring buffer used by the file sink.
+    pub fn with_buffer_size(mut self, size: usize) -> Self {
+        self.file_buffer_size = size;
+        self
+    }
+
+    /// Enables `flate2` deflate compression of the file sink's output.
+    pub fn with_compression(mut self, compression: bool) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Routes records to a user-supplied [`Sink`] (in-memory buffer, rotating
+    /// file handle, custom destination) instead of stdout/stderr.
+    pub fn with_sink(mut self, sink: Box<dyn Sink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Forwards records to a remote collector over a non-blocking TCP socket.
+    ///
+    /// The connection is established lazily on the writer thread and
+    /// re-established with backoff if the peer resets it, so the application is
+    /// never blocked on the network.
+    pub fn with_tcp<A: std::net::ToSocketAddrs>(mut self, addr: A) -> Self {
+        self.sink = Some(Box::new(TcpSink::new(addr)));
+        self
+    }
+
+    /// Registers an additional routed sink: records matching `filter` are
+    /// delivered to `sink` in addition to the default destination. A record
+    /// matching several routes is delivered to each. Composes with the global
+    /// [`with_level`](Self::with_level).
+    ///
+    /// Any [`Sink`] works here, including a [`FileWriter`], so the headline
+    /// routing case — `error!` to a file sink while everything still goes to
+    /// stdout/stderr (warnings and errors to stderr, the rest to stdout) — is
+    /// expressed as:
+    ///
+    /// ```no_run
+    /// # use log_nonblock::{NonBlockingLogger, Filter, FileWriter};
+    /// # use log::LevelFilter;
+    /// let file = FileWriter::new("errors.log".as_ref(), 32 << 20, false)?;
+    /// let _logger = NonBlockingLogger::builder()
+    ///     .add_route(Filter::new().with_min_severity(LevelFilter::Error), Box::new(file))
+    ///     .init()?;
+    /// # Ok::<(), Box<dyn std::error::Error>>(())
+    /// ```
+    pub fn add_route(mut self, filter: Filter, sink: Box<dyn Sink>) -> Self {
+        self.routes.push((filter, sink));
+        self
+    }
+
+    /// Spawns a writer thread draining a fresh buffer into `output`.
+    fn spawn_writer(&self, output: Output) -> (Arc<Buffer>, JoinHandle<()>) {
+        let buffer = Arc::new(Buffer::new(self.capacity, self.buffer_bytes, self.policy));
+        let writer_buffer = Arc::clone(&buffer);
+        let worker = thread::Builder::new()
+            .name("log_nonblock-writer".to_string())
+            .spawn(move || writer_loop(writer_buffer, output))
+            .expect("failed to spawn log_nonblock writer thread");
+        (buffer, worker)
+    }
+
+    /// Spawns the writer thread(s) and installs the logger into the `log` facade.
+    pub fn init(mut self) -> Result<NonBlockingLogger, InitError> {
+        let routes = std::mem::take(&mut self.routes);
+
+        // Resolve the default destination.
+        let (output, keep_file) = if let Some(ref path) = self.file {
+            let writer = Arc::new(FileWriter::new(
+                path,
+                self.file_buffer_size,
+                self.compression,
+            )?);
+            (Output::File(Arc::clone(&writer)), Some(writer))
+        } else if let Some(sink) = self.sink.take() {
+            (Output::Custom(sink), None)
+        } else {
+            (Output::Stdio, None)
+        };
+
+        let mut channels = Vec::with_capacity(routes.len() + 1);
+        let mut workers = Vec::with_capacity(routes.len() + 1);
+
+        let (default_buffer, default_worker) = self.spawn_writer(output);
+        channels.push(Channel {
+            filter: None,
+            buffer: Arc::clone(&default_buffer),
+        });
+        workers.push(default_worker);
+
+        // One writer thread and buffer per additional route.
+        for (filter, sink) in routes {
+            let (buffer, worker) = self.spawn_writer(Output::Custom(sink));
+            channels.push(Channel {
+                filter: Some(filter),
+                buffer,
+            });
+            workers.push(worker);
+        }
+
+        let buffers: Vec<Arc<Buffer>> = channels.iter().map(|c| Arc::clone(&c.buffer)).collect();
+
+        let adapter = LogAdapter {
+            channels,
+            level: self.level,
+            timestamps: self.timestamps,
+        };
+
+        log::set_boxed_logger(Box::new(adapter))?;
+        log::set_max_level(self.level);
+
+        Ok(NonBlockingLogger {
+            default_buffer,
+            buffers,
+            workers,
+            _file: keep_file,
+        })
+    }
+}
+
+/// A handle to a running non-blocking logger.
+///
+/// Keep it alive for as long as logging is needed; dropping it shuts the writer
+/// thread down cleanly and joins it so no buffered record is lost.
+pub struct NonBlockingLogger {
+    default_buffer: Arc<Buffer>,
+    buffers: Vec<Arc<Buffer>>,
+    workers: Vec<JoinHandle<()>>,
+    // Kept alive so the file sink flushes and joins when the logger is dropped.
+    _file: Option<Arc<FileWriter>>,
+}
+
+impl NonBlockingLogger {
+    /// Returns a builder for configuring and installing the logger.
+    pub fn builder() -> NonBlockingLoggerBuilder {
+        NonBlockingLoggerBuilder::new()
+    }
+
+    /// Returns the number of records dropped from the default destination's
+    /// buffer due to overflow and not yet reported. The writer thread resets
+    /// this count each time it emits its "dropped N messages" record, so it is
+    /// not a running total.
+    pub fn dropped(&self) -> usize {
+        self.default_buffer.dropped()
+    }
+}
+
+impl Drop for NonBlockingLogger {
+    fn drop(&mut self) {
+        // Ask every writer to drain and stop, then join so pending records flush.
+        for buffer in &self.buffers {
+            buffer.push_control(Message::Shutdown);
+        }
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::Mutex;
+
+    /// An in-memory [`Sink`] that records everything written to it, used to
+    /// observe what the writer thread actually drained.
+    #[derive(Clone)]
+    struct TestSink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for TestSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Sink for TestSink {}
+
+    fn line(text: &str) -> Line {
+        Line {
+            stream: Stream::Stdout,
+            text: text.to_string(),
+        }
+    }
+
+    /// Sends a flush barrier and blocks until the writer acknowledges it.
+    fn drain(buffer: &Buffer) {
+        let (ack_tx, ack_rx) = sync_channel(0);
+        buffer.push_control(Message::Flush(ack_tx));
+        ack_rx.recv().unwrap();
+    }
+
+    #[test]
+    fn flush_barrier_waits_for_all_pending_records() {
+        let buffer = Arc::new(Buffer::new(1024, 1 << 20, OverflowPolicy::Block));
+        let captured = Arc::new(Mutex::new(Vec::new()));
+        let sink = TestSink(Arc::clone(&captured));
+
+        let writer_buffer = Arc::clone(&buffer);
+        let worker = thread::spawn(move || writer_loop(writer_buffer, Output::Custom(Box::new(sink))));
+
+        for i in 0..100 {
+            buffer.push_record(line(&format!("line {}\n", i)));
+        }
+        // Once flush returns, every record above must already be in the sink.
+        drain(&buffer);
+
+        let text = String::from_utf8(captured.lock().unwrap().clone()).unwrap();
+        assert_eq!(text.lines().count(), 100);
+        assert!(text.starts_with("line 0\n"));
+        assert!(text.trim_end().ends_with("line 99"));
+
+        buffer.push_control(Message::Shutdown);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn drop_oldest_evicts_oldest_and_counts() {
+        let buffer = Buffer::new(3, 1 << 20, OverflowPolicy::DropOldest);
+        for i in 0..5 {
+            buffer.push_record(line(&format!("m{}", i)));
+        }
+        // Capacity is three records; pushing five evicts the two oldest.
+        assert_eq!(buffer.dropped(), 2);
+        for expected in ["m2", "m3", "m4"] {
+            match buffer.pop() {
+                Message::Record(l) => assert_eq!(l.text, expected),
+                _ => panic!("expected a record"),
+            }
+        }
+    }
+
+    #[test]
+    fn drop_newest_discards_incoming_and_counts() {
+        let buffer = Buffer::new(2, 1 << 20, OverflowPolicy::DropNewest);
+        for i in 0..5 {
+            buffer.push_record(line(&format!("m{}", i)));
+        }
+        // The first two are retained; the three later arrivals are dropped.
+        assert_eq!(buffer.dropped(), 3);
+        for expected in ["m0", "m1"] {
+            match buffer.pop() {
+                Message::Record(l) => assert_eq!(l.text, expected),
+                _ => panic!("expected a record"),
+            }
+        }
+    }
+
+    #[test]
+    fn take_dropped_reads_and_resets() {
+        let buffer = Buffer::new(1, 1 << 20, OverflowPolicy::DropNewest);
+        buffer.push_record(line("a"));
+        buffer.push_record(line("b"));
+        assert_eq!(buffer.take_dropped(), 1);
+        assert_eq!(buffer.dropped(), 0);
+    }
+
+    fn matches(filter: &Filter, level: log::Level, target: &str, thread_id: ThreadId) -> bool {
+        filter.matches(
+            &Record::builder()
+                .level(level)
+                .target(target)
+                .args(format_args!("x"))
+                .build(),
+            thread_id,
+        )
+    }
+
+    #[test]
+    fn filter_severity_is_inclusive_at_the_boundary() {
+        let here = thread::current().id();
+        let filter = Filter::new().with_min_severity(LevelFilter::Warn);
+        assert!(matches(&filter, log::Level::Error, "t", here));
+        assert!(matches(&filter, log::Level::Warn, "t", here));
+        assert!(!matches(&filter, log::Level::Info, "t", here));
+    }
+
+    #[test]
+    fn filter_tag_matches_target_substring() {
+        let here = thread::current().id();
+        let filter = Filter::new().with_tag("net");
+        assert!(matches(&filter, log::Level::Info, "app::net::tcp", here));
+        assert!(!matches(&filter, log::Level::Info, "app::db", here));
+    }
+
+    #[test]
+    fn filter_thread_id_restricts_origin() {
+        let here = thread::current().id();
+        let other = thread::spawn(|| thread::current().id()).join().unwrap();
+        let filter = Filter::new().with_thread_id(here);
+        assert!(matches(&filter, log::Level::Info, "t", here));
+        assert!(!matches(&filter, log::Level::Info, "t", other));
+    }
+}
@@ -0,0 +1,172 @@
+//! A non-blocking remote TCP sink.
+//!
+//! Reuses the Unix [`set_nonblocking`](crate::set_nonblocking) helper to drive
+//! a `TcpStream` in non-blocking mode and ships newline-delimited log lines to
+//! a remote collector. Like the retry loop used for stdio, the sink's own write
+//! loop polls `POLLOUT` on `WouldBlock`; a connection reset triggers an automatic reconnect with
+//! exponential backoff. All of this runs on the writer thread, so the
+//! application's logging calls never block on the network.
+
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+#[cfg(unix)]
+use std::os::fd::AsRawFd;
+
+use crate::io as retry_io;
+use crate::Sink;
+
+/// Initial reconnect backoff.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Maximum reconnect backoff.
+const MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+/// Maximum number of connection passes a single `write` will spend reconnecting
+/// before giving up on the record. Bounding this keeps the writer thread from
+/// parking forever inside one record while the collector is down, so the thread
+/// can still pop `Shutdown` and the logger can be dropped.
+const MAX_RECONNECT_ATTEMPTS: u32 = 5;
+
+/// A [`Sink`] that forwards log lines to a remote collector over TCP.
+pub struct TcpSink {
+    addrs: Vec<SocketAddr>,
+    stream: Option<TcpStream>,
+    backoff: Duration,
+}
+
+impl TcpSink {
+    /// Creates a sink targeting `addr`. The connection is established lazily on
+    /// the first write and re-established automatically after a reset.
+    pub fn new<A: ToSocketAddrs>(addr: A) -> Self {
+        let addrs = addr
+            .to_socket_addrs()
+            .map(|iter| iter.collect())
+            .unwrap_or_default();
+        TcpSink {
+            addrs,
+            stream: None,
+            backoff: INITIAL_BACKOFF,
+        }
+    }
+
+    /// Attempts a single connection pass over the configured addresses, putting
+    /// the socket into non-blocking mode on success.
+    fn connect(&self) -> Option<TcpStream> {
+        for addr in &self.addrs {
+            if let Ok(stream) = TcpStream::connect(addr) {
+                #[cfg(unix)]
+                {
+                    let _ = retry_io::set_nonblocking(stream.as_raw_fd());
+                }
+                return Some(stream);
+            }
+        }
+        None
+    }
+
+    /// Reconnects with exponential backoff, for up to [`MAX_RECONNECT_ATTEMPTS`]
+    /// passes. Returns `true` once connected, or `false` once the attempts are
+    /// exhausted so the caller can give up on the record instead of blocking the
+    /// writer thread indefinitely.
+    fn reconnect(&mut self) -> bool {
+        for attempt in 0..MAX_RECONNECT_ATTEMPTS {
+            if let Some(stream) = self.connect() {
+                self.stream = Some(stream);
+                self.backoff = INITIAL_BACKOFF;
+                return true;
+            }
+            if attempt + 1 < MAX_RECONNECT_ATTEMPTS {
+                thread::sleep(self.backoff);
+                self.backoff = std::cmp::min(self.backoff * 2, MAX_BACKOFF);
+            }
+        }
+        false
+    }
+}
+
+impl Write for TcpSink {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        loop {
+            if self.stream.is_none() && !self.reconnect() {
+                // Collector unreachable: give up on this record with a hard
+                // error (not WouldBlock, which the retry loop would spin on) so
+                // the writer thread can move on and honour Shutdown.
+                return Err(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "log_nonblock: remote collector unreachable",
+                ));
+            }
+            let stream = self.stream.as_mut().unwrap();
+            #[cfg(unix)]
+            let fd = stream.as_raw_fd();
+
+            let mut written = 0;
+            while written < bytes.len() {
+                match stream.write(&bytes[written..]) {
+                    Ok(0) => {
+                        #[cfg(unix)]
+                        if retry_io::wait_writable(fd).is_err() {
+                            return Ok(written);
+                        }
+                    }
+                    Ok(n) => written += n,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                        #[cfg(unix)]
+                        if retry_io::wait_writable(fd).is_err() {
+                            return Ok(written);
+                        }
+                    }
+                    Err(ref e)
+                        if matches!(
+                            e.kind(),
+                            io::ErrorKind::ConnectionReset | io::ErrorKind::BrokenPipe
+                        ) =>
+                    {
+                        // Peer went away: drop the socket and reconnect, then
+                        // retry the whole record from the outer loop.
+                        self.stream = None;
+                        break;
+                    }
+                    Err(_) => return Ok(written),
+                }
+            }
+
+            if self.stream.is_some() {
+                return Ok(bytes.len());
+            }
+            // Fell through because of a reset; loop to reconnect and resend.
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stream {
+            Some(ref mut stream) => stream.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Sink for TcpSink {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn write_gives_up_when_collector_is_unreachable() {
+        // Bind then immediately drop to obtain an address nothing listens on.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let mut sink = TcpSink::new(addr);
+        // After the bounded reconnect attempts, the write surfaces a hard error
+        // instead of parking the writer thread forever.
+        let err = sink.write(b"hello\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    }
+}